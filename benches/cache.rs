@@ -0,0 +1,79 @@
+//! Guards the cached fast path: with a valid cached token, `wrapperauth auth`
+//! must answer without starting AzureAuth, in single-digit milliseconds.
+//!
+//! Run with `cargo bench`. Unix only, since it fakes AzureAuth with a shell
+//! script.
+
+#![cfg_attr(not(unix), allow(dead_code, unused_imports))]
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 100;
+const BUDGET: Duration = Duration::from_millis(10);
+
+// {"aud":"https://graph.microsoft.com","scp":"User.Read","exp":4102444800}
+const TOKEN: &str = "e30.eyJhdWQiOiJodHRwczovL2dyYXBoLm1pY3Jvc29mdC5jb20iLCJzY3AiOiJVc2VyLlJlYWQiLCJleHAiOjQxMDI0NDQ4MDB9.sig";
+
+#[cfg(unix)]
+fn fake_azureauth(dir: &Path, script: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("azureauth");
+    fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn wrapperauth(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_wrapperauth"));
+    command
+        .args(["auth", "--client", "foo", "--tenant", "bar"])
+        .args(["--scopes", "https://graph.microsoft.com/User.Read"])
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", dir)
+        .env("HOME", dir)
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("XDG_DATA_HOME", dir.join("data"));
+    command
+}
+
+#[cfg(unix)]
+fn main() {
+    let dir = std::env::temp_dir().join(format!("wrapperauth-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    // Warm the cache, then make sure any fall back to AzureAuth would fail.
+    fake_azureauth(&dir, &format!(r#"echo '{{"token": "{TOKEN}"}}'"#));
+    let warm = wrapperauth(&dir).output().unwrap();
+    assert!(warm.status.success(), "failed to warm the cache: {warm:?}");
+    fake_azureauth(&dir, "exit 1");
+
+    let mut timings: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = wrapperauth(&dir).output().unwrap();
+            let elapsed = start.elapsed();
+            assert!(output.status.success(), "cache miss: {output:?}");
+            assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), TOKEN);
+            elapsed
+        })
+        .collect();
+    fs::remove_dir_all(&dir).unwrap();
+
+    timings.sort_unstable();
+    let median = timings[RUNS / 2];
+    println!(
+        "cached auth: median {median:?}, min {:?}, max {:?} over {RUNS} runs",
+        timings[0],
+        timings[RUNS - 1]
+    );
+    assert!(median < BUDGET, "median {median:?} exceeds {BUDGET:?}");
+}
+
+#[cfg(not(unix))]
+fn main() {
+    println!("cached auth benchmark only runs on Unix");
+}
@@ -0,0 +1,175 @@
+//! Choosing between accounts when several have tokens in our cache.
+//!
+//! AzureAuth can't be told which account to use and doesn't list the
+//! broker's accounts, so only tokens we've cached ourselves can be chosen.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::token::TokenResult;
+
+/// UPNs aren't case sensitive, and AzureAuth doesn't always agree on a case.
+fn same_upn(user: &str, account: &str) -> bool {
+    user.eq_ignore_ascii_case(account)
+}
+
+/// Check whether a token belongs to the given account's UPN.
+pub fn matches(result: &TokenResult, account: &str) -> bool {
+    result
+        .user
+        .as_deref()
+        .is_some_and(|user| same_upn(user, account))
+}
+
+/// Check whether two tokens belong to the same account, counting tokens for
+/// unknown accounts as the same.
+pub fn same_account(a: &TokenResult, b: &TokenResult) -> bool {
+    match (a.user.as_deref(), b.user.as_deref()) {
+        (Some(a), Some(b)) => same_upn(a, b),
+        (a, b) => a == b,
+    }
+}
+
+fn describe(result: &TokenResult) -> String {
+    let user = result.user.as_deref().unwrap_or("unknown account");
+    match &result.display_name {
+        Some(display_name) => format!("{user} ({display_name})"),
+        None => String::from(user),
+    }
+}
+
+/// Pick a cached token: the requested account's, the only one there is, or
+/// whichever the user chooses.
+///
+/// `None` means there's nothing suitable cached and a fresh token is needed.
+pub fn select(
+    mut cached: Vec<TokenResult>,
+    account: Option<&str>,
+) -> Result<Option<TokenResult>, String> {
+    if let Some(account) = account {
+        return Ok(cached.into_iter().find(|result| matches(result, account)));
+    }
+
+    match cached.len() {
+        0 | 1 => Ok(cached.pop()),
+        _ if io::stdin().is_terminal() => {
+            pick(cached, io::stdin().lock(), io::stderr().lock()).map(Some)
+        }
+        _ => {
+            let accounts: Vec<String> = cached.iter().map(describe).collect();
+            Err(format!(
+                "Several accounts have cached tokens: {}. Pick one with --account.",
+                accounts.join(", ")
+            ))
+        }
+    }
+}
+
+fn pick(
+    mut cached: Vec<TokenResult>,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<TokenResult, String> {
+    let error = |err: io::Error| format!("Failed to pick an account: {err}");
+    let count = cached.len();
+
+    writeln!(output, "Several accounts have cached tokens:").map_err(error)?;
+    for (i, result) in cached.iter().enumerate() {
+        writeln!(output, "  {}) {}", i + 1, describe(result)).map_err(error)?;
+    }
+    loop {
+        write!(output, "Account [1-{count}]: ").map_err(error)?;
+        output.flush().map_err(error)?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer).map_err(error)? == 0 {
+            return Err(String::from("No account picked."));
+        }
+        match answer.trim().parse() {
+            Ok(n) if (1..=count).contains(&n) => return Ok(cached.swap_remove(n - 1)),
+            _ => writeln!(output, "Pick a number from 1 to {count}.").map_err(error)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick, same_account, select};
+    use crate::token::TokenResult;
+    use pretty_assertions::assert_eq;
+
+    fn result(user: &str) -> TokenResult {
+        TokenResult {
+            user: Some(String::from(user)),
+            display_name: None,
+            token: format!("{user}-token"),
+            expiration_date: None,
+        }
+    }
+
+    #[test]
+    fn select_account() {
+        let cached = vec![result("foo@example.com"), result("bar@example.com")];
+        let subject = select(cached, Some("BAR@example.com"));
+
+        assert_eq!(subject, Ok(Some(result("bar@example.com"))));
+    }
+
+    #[test]
+    fn select_missing_account() {
+        let cached = vec![result("foo@example.com")];
+        let subject = select(cached, Some("bar@example.com"));
+
+        assert_eq!(subject, Ok(None));
+    }
+
+    #[test]
+    fn select_only_account() {
+        assert_eq!(select(Vec::new(), None), Ok(None));
+        assert_eq!(
+            select(vec![result("foo@example.com")], None),
+            Ok(Some(result("foo@example.com")))
+        );
+    }
+
+    #[test]
+    fn same_account_ignores_case() {
+        let unknown = TokenResult {
+            user: None,
+            ..result("foo@example.com")
+        };
+
+        assert!(same_account(
+            &result("Foo@example.com"),
+            &result("foo@example.com")
+        ));
+        assert!(!same_account(
+            &result("foo@example.com"),
+            &result("bar@example.com")
+        ));
+        assert!(!same_account(&result("foo@example.com"), &unknown));
+        assert!(same_account(&unknown, &unknown));
+    }
+
+    #[test]
+    fn pick_account() {
+        let cached = vec![result("foo@example.com"), result("bar@example.com")];
+        let mut output = Vec::new();
+        let subject = pick(cached, &b"3\nnope\n2\n"[..], &mut output);
+
+        assert_eq!(subject, Ok(result("bar@example.com")));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Several accounts have cached tokens:\n  1) foo@example.com\n  2) bar@example.com\n\
+             Account [1-2]: Pick a number from 1 to 2.\n\
+             Account [1-2]: Pick a number from 1 to 2.\n\
+             Account [1-2]: "
+        );
+    }
+
+    #[test]
+    fn pick_nothing() {
+        let cached = vec![result("foo@example.com"), result("bar@example.com")];
+        let subject = pick(cached, &b""[..], Vec::new());
+
+        assert!(subject.is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! `wrapperauth`'s own token cache.
+//!
+//! Starting AzureAuth costs far more than anything else we do, even when
+//! its cache is warm, so a valid token here means we never start it at all.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account;
+use crate::dirs;
+use crate::token::TokenResult;
+
+/// Tokens this close to expiring aren't handed out, so callers don't race
+/// the expiry.
+const MARGIN: u64 = 5 * 60;
+
+fn path(key: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(key))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Look up every account's token which is still good.
+pub fn get(key: &str) -> Vec<TokenResult> {
+    path(key)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map_or_else(Vec::new, |contents| parse(&contents, now()))
+}
+
+fn parse(contents: &str, now: u64) -> Vec<TokenResult> {
+    let Ok(results) = serde_json::from_str::<Vec<TokenResult>>(contents) else {
+        return Vec::new();
+    };
+    results
+        .into_iter()
+        .filter(|result| !result.token.is_empty())
+        .filter(|result| {
+            result
+                .expiration_date
+                .is_some_and(|date| date > now + MARGIN)
+        })
+        .collect()
+}
+
+/// Store a token until it expires, replacing any older token for the same
+/// account.
+pub fn put(key: &str, result: &TokenResult) -> Result<(), String> {
+    let mut results = get(key);
+    results.retain(|cached| !account::same_account(cached, result));
+    results.push(result.clone());
+    write(key, &results)
+}
+
+fn write(key: &str, results: &[TokenResult]) -> Result<(), String> {
+    let path = path(key).ok_or_else(|| String::from("Could not locate a cache directory"))?;
+    let error = |err: io::Error| format!("Failed to cache token in {}: {err}", path.display());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+
+    // Write then rename, so concurrent callers never read half a token.
+    let partial = path.with_extension(format!("{}.tmp", process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial).map_err(error)?;
+    let results = serde_json::to_string(results).expect("token results serialize");
+    writeln!(file, "{results}").map_err(error)?;
+    fs::rename(&partial, &path).map_err(error)
+}
+
+/// Forget one particular token.
+pub fn evict(key: &str, result: &TokenResult) -> Result<(), String> {
+    let mut results = get(key);
+    results.retain(|cached| cached.token != result.token);
+    write(key, &results)
+}
+
+/// Forget the token for an account, or for every account.
+pub fn remove(key: &str, account: Option<&str>) -> Result<(), String> {
+    if let Some(account) = account {
+        let mut results = get(key);
+        results.retain(|cached| !account::matches(cached, account));
+        return write(key, &results);
+    }
+
+    let Some(path) = path(key) else {
+        return Ok(());
+    };
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(format!(
+            "Failed to remove cached token {}: {err}",
+            path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, MARGIN};
+    use crate::token::TokenResult;
+    use pretty_assertions::assert_eq;
+
+    fn result(user: &str, expiration_date: Option<u64>) -> TokenResult {
+        TokenResult {
+            user: Some(String::from(user)),
+            display_name: None,
+            token: String::from("opaque"),
+            expiration_date,
+        }
+    }
+
+    fn contents(results: &[TokenResult]) -> String {
+        serde_json::to_string(results).unwrap()
+    }
+
+    #[test]
+    fn parse_valid_tokens() {
+        let results = [result("foo", Some(2000)), result("bar", Some(3000))];
+
+        assert_eq!(parse(&contents(&results), 1000), results);
+    }
+
+    #[test]
+    fn parse_expiring_token() {
+        let results = [result("foo", Some(2000)), result("bar", Some(3000))];
+
+        assert_eq!(parse(&contents(&results), 2000 - MARGIN), &results[1..]);
+    }
+
+    #[test]
+    fn parse_garbage() {
+        assert_eq!(parse(&contents(&[result("foo", None)]), 1000), []);
+        assert_eq!(parse("2000\ntoken\n", 1000), []);
+    }
+}
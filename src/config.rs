@@ -0,0 +1,244 @@
+//! Per-directory `.wrapperauth.toml` files.
+//!
+//! Like direnv, the first file found walking up from the current directory
+//! wins, and a file has to be trusted before it's used. Trust is tied to the
+//! file's contents, so editing a trusted file means trusting it again.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::dirs;
+
+pub const FILE_NAME: &str = ".wrapperauth.toml";
+
+/// Values pinned by a config file.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub client: Option<String>,
+    pub tenant: Option<String>,
+    pub scopes: Vec<String>,
+    pub b2c_policy: Option<String>,
+    /// `AZUREAUTH_*` variables to set for AzureAuth, from the `[env]` table.
+    pub env: BTreeMap<String, String>,
+    /// The directory holding the config file, which `@file` scopes are
+    /// relative to.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+/// Find the nearest config file in `start` or any of its ancestors.
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Read a config file, asking the user to trust it if they haven't already.
+///
+/// Like direnv, an untrusted file is skipped with a warning when there's
+/// nobody to ask, so scripts keep working when a repo adds one.
+pub fn load(path: &Path) -> Result<Config, String> {
+    load_from(path, io::stdin().is_terminal())
+}
+
+fn load_from(path: &Path, interactive: bool) -> Result<Config, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    if !is_trusted(path, &contents) {
+        if !interactive {
+            eprintln!(
+                "Warning: Ignoring {}, which is new or has changed. Run wrapperauth \
+                 interactively to review and trust it.",
+                path.display()
+            );
+            return Ok(Config::default());
+        }
+        if !prompt(path)? {
+            return Err(format!("Refusing to use untrusted {}", path.display()));
+        }
+        trust(path, &contents)?;
+    }
+    let mut config =
+        parse(&contents).map_err(|err| format!("Invalid {}: {err}", path.display()))?;
+    config.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok(config)
+}
+
+fn trust_file() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("trusted"))
+}
+
+/// Trust is keyed on a cryptographic hash, so nobody who can write the file
+/// can swap in different contents that still look trusted.
+fn trust_entry(path: &Path, contents: &str) -> String {
+    let hash: String = Sha256::digest(contents.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{hash} {}", path.display())
+}
+
+fn is_trusted(path: &Path, contents: &str) -> bool {
+    let entry = trust_entry(path, contents);
+    trust_file()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .is_some_and(|trusted| trusted.lines().any(|line| line == entry))
+}
+
+fn trust(path: &Path, contents: &str) -> Result<(), String> {
+    let file = trust_file().ok_or_else(|| String::from("Could not locate a data directory"))?;
+    let error = |err: io::Error| format!("Failed to record trust in {}: {err}", file.display());
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).map_err(error)?;
+    }
+    let mut trusted = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file)
+        .map_err(error)?;
+    writeln!(trusted, "{}", trust_entry(path, contents)).map_err(error)
+}
+
+fn prompt(path: &Path) -> Result<bool, String> {
+    eprint!("{} is new or has changed. Trust it? [y/N] ", path.display());
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|err| format!("Failed to read answer: {err}"))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Parse a config file's contents.
+pub fn parse(contents: &str) -> Result<Config, String> {
+    let config: Config = toml::from_str(contents).map_err(|err| err.message().to_owned())?;
+    if let Some(key) = config.env.keys().find(|key| !key.starts_with("AZUREAUTH_")) {
+        return Err(format!("`{key}` in [env] must start with AZUREAUTH_"));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discover, load_from, parse, trust_entry, Config, FILE_NAME};
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::{env, fs};
+
+    #[test]
+    fn parse_config() {
+        let contents = r#"
+            # Pinned for this repo.
+            client = "foo"
+            tenant = 'bar' # Trailing comment.
+            scopes = [
+                "baz",
+                "quux", # Trailing comma.
+            ]
+            b2c_policy = "B2C_1_signin"
+
+            [env]
+            AZUREAUTH_CONFIG = "/etc/azureauth.toml"
+        "#;
+        let expected = Config {
+            client: Some(String::from("foo")),
+            tenant: Some(String::from("bar")),
+            scopes: vec![String::from("baz"), String::from("quux")],
+            b2c_policy: Some(String::from("B2C_1_signin")),
+            env: BTreeMap::from([(
+                String::from("AZUREAUTH_CONFIG"),
+                String::from("/etc/azureauth.toml"),
+            )]),
+            dir: PathBuf::new(),
+        };
+
+        assert_eq!(parse(contents), Ok(expected));
+    }
+
+    #[test]
+    fn parse_partial_config() {
+        let expected = Config {
+            tenant: Some(String::from("bar")),
+            ..Config::default()
+        };
+
+        assert_eq!(parse("tenant = \"bar\""), Ok(expected));
+    }
+
+    #[test]
+    fn parse_dotted_and_quoted_keys() {
+        let expected = Config {
+            client: Some(String::from("foo")),
+            env: BTreeMap::from([(String::from("AZUREAUTH_CONFIG"), String::from("\u{e9}"))]),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            parse("\"client\" = \"foo\"\nenv.AZUREAUTH_CONFIG = \"\\u00e9\""),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_keys() {
+        let subject = parse("clinet = \"foo\"").unwrap_err();
+
+        assert!(subject.contains("clinet"), "{subject}");
+    }
+
+    #[test]
+    fn parse_rejects_other_env() {
+        assert!(parse("[env]\nLD_PRELOAD = \"evil.so\"").is_err());
+        assert!(parse("[other]").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_trailing_values() {
+        assert!(parse("client = \"foo\" \"bar\"").is_err());
+    }
+
+    #[test]
+    fn trust_entry_sha256() {
+        let subject = trust_entry(Path::new("/repo/.wrapperauth.toml"), "abc");
+
+        assert_eq!(
+            subject,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad /repo/.wrapperauth.toml"
+        );
+    }
+
+    #[test]
+    fn load_untrusted_without_terminal() {
+        let dir = env::temp_dir().join(format!("wrapperauth-untrusted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(FILE_NAME);
+        fs::write(&path, format!("client = \"{}\"", dir.display())).unwrap();
+
+        let subject = load_from(&path, false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(subject, Ok(Config::default()));
+    }
+
+    #[test]
+    fn discover_nearest_ancestor() {
+        let root = env::temp_dir().join(format!("wrapperauth-discover-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(FILE_NAME), "").unwrap();
+        fs::write(root.join("a").join(FILE_NAME), "").unwrap();
+
+        let subject = discover(&nested);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(subject, Some(root.join("a").join(FILE_NAME)));
+    }
+}
@@ -0,0 +1,26 @@
+//! Where `wrapperauth` keeps its own files.
+
+use std::env;
+use std::path::PathBuf;
+
+fn home() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Persistent data, like the list of trusted config files.
+pub fn data_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(".local").join("share")))
+        .map(|dir| dir.join("wrapperauth"))
+}
+
+/// Disposable data, like cached tokens.
+pub fn cache_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(".cache")))
+        .map(|dir| dir.join("wrapperauth"))
+}
@@ -0,0 +1,134 @@
+//! The environment AzureAuth runs in.
+//!
+//! Rather than inheriting everything, AzureAuth only sees an allow-list of
+//! the caller's variables, so secrets and stray settings can't leak into or
+//! change the behavior of the auth process.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::process::Command;
+
+/// Variables AzureAuth needs from the caller.
+const ALLOWED: &[&str] = &[
+    // Finding and running programs.
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TMPDIR",
+    "DOTNET_ROOT",
+    // Reaching the network.
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "all_proxy",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+    // Trusting a corporate proxy's certificates.
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    // Opening a browser and the keyring for interactive auth on Linux.
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+    // Windows, which .NET can't start or find framework installs without.
+    "SystemRoot",
+    "SystemDrive",
+    "windir",
+    "PATHEXT",
+    "ProgramFiles",
+    "ProgramFiles(x86)",
+    "ProgramW6432",
+    "USERPROFILE",
+    "HOMEDRIVE",
+    "HOMEPATH",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "TEMP",
+    "TMP",
+];
+
+fn allowed(name: &OsString) -> bool {
+    let name = name.to_string_lossy();
+    if cfg!(windows) {
+        ALLOWED
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&name))
+    } else {
+        ALLOWED.contains(&name.as_ref())
+    }
+}
+
+/// Set up AzureAuth's environment from the caller's `vars`, keeping only
+/// allowed variables unless `inherit` is set, then applying `overrides`.
+pub fn apply(
+    command: &mut Command,
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+    overrides: &BTreeMap<String, String>,
+    inherit: bool,
+) {
+    if !inherit {
+        command.env_clear();
+        command.envs(vars.into_iter().filter(|(name, _)| allowed(name)));
+    }
+    command.envs(overrides);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply;
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+    use std::ffi::{OsStr, OsString};
+    use std::process::Command;
+
+    fn vars() -> Vec<(OsString, OsString)> {
+        [
+            ("PATH", "/bin"),
+            ("https_proxy", "http://proxy"),
+            ("SSL_CERT_FILE", "/etc/ca.pem"),
+            ("GITHUB_TOKEN", "secret"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+        .collect()
+    }
+
+    fn envs(command: &Command) -> Vec<(&OsStr, Option<&OsStr>)> {
+        let mut envs: Vec<_> = command.get_envs().collect();
+        envs.sort();
+        envs
+    }
+
+    #[test]
+    fn apply_allow_list() {
+        let overrides = BTreeMap::from([(String::from("AZUREAUTH_FOO"), String::from("bar"))]);
+        let mut command = Command::new("azureauth");
+        apply(&mut command, vars(), &overrides, false);
+
+        assert_eq!(
+            envs(&command),
+            [
+                (OsStr::new("AZUREAUTH_FOO"), Some(OsStr::new("bar"))),
+                (OsStr::new("PATH"), Some(OsStr::new("/bin"))),
+                (OsStr::new("SSL_CERT_FILE"), Some(OsStr::new("/etc/ca.pem"))),
+                (OsStr::new("https_proxy"), Some(OsStr::new("http://proxy"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_inherit() {
+        let overrides = BTreeMap::from([(String::from("AZUREAUTH_FOO"), String::from("bar"))]);
+        let mut command = Command::new("azureauth");
+        apply(&mut command, vars(), &overrides, true);
+
+        assert_eq!(
+            envs(&command),
+            [(OsStr::new("AZUREAUTH_FOO"), Some(OsStr::new("bar")))]
+        );
+    }
+}
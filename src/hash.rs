@@ -0,0 +1,9 @@
+//! Hashing which stays stable across builds of `wrapperauth`, unlike std's
+//! `DefaultHasher`, so it's safe to persist.
+
+/// 64-bit FNV-1a.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,249 @@
+//! Decoding the claims of an access token, without verifying its signature.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+
+/// The Microsoft Graph application ID, used as `aud` by v2 Graph tokens.
+const GRAPH_APP_ID: &str = "00000003-0000-0000-c000-000000000000";
+const GRAPH_RESOURCE: &str = "https://graph.microsoft.com";
+
+/// OpenID Connect scopes which never show up in an access token's `scp`.
+const OIDC_SCOPES: [&str; 4] = ["openid", "profile", "offline_access", "email"];
+
+/// The claims we care about from an access token.
+#[derive(Debug, PartialEq)]
+pub struct Claims {
+    pub aud: Vec<String>,
+    pub scp: Vec<String>,
+    pub exp: Option<u64>,
+    /// The signed in user's UPN, if the token is for one.
+    pub user: Option<String>,
+}
+
+impl Claims {
+    /// Check whether these claims grant the given requested scope.
+    ///
+    /// Fails if the audience can't be matched up with the requested resource
+    /// either way, so there's no telling.
+    pub fn covers(&self, scope: &str) -> Result<bool, String> {
+        if OIDC_SCOPES.iter().any(|s| s.eq_ignore_ascii_case(scope)) {
+            return Ok(true);
+        }
+
+        let (resource, permission) = scope.rsplit_once('/').unwrap_or((GRAPH_RESOURCE, scope));
+        let audiences: Vec<Option<bool>> = self
+            .aud
+            .iter()
+            .map(|aud| audience_matches(aud, resource))
+            .collect();
+        if audiences.contains(&None) && !audiences.contains(&Some(true)) {
+            return Err(format!(
+                "could not verify audience {} for {scope}",
+                self.aud.join(", ")
+            ));
+        }
+        if !audiences.contains(&Some(true)) {
+            return Ok(false);
+        }
+
+        Ok(permission == ".default" || self.scp.iter().any(|s| s.eq_ignore_ascii_case(permission)))
+    }
+}
+
+/// Check whether `aud` is the requested resource, or `None` if there's no
+/// telling.
+///
+/// v2 tokens for custom APIs carry the API's client ID as `aud`, whatever App
+/// ID URI was requested. Unless the URI names an app ID itself there's no
+/// mapping one to the other.
+fn audience_matches(aud: &str, resource: &str) -> Option<bool> {
+    let aud = aud.trim_end_matches('/');
+    let resource = resource.trim_end_matches('/');
+    if aud.eq_ignore_ascii_case(resource) {
+        return Some(true);
+    }
+    if resource.eq_ignore_ascii_case(GRAPH_RESOURCE) {
+        return Some(aud == GRAPH_APP_ID);
+    }
+
+    let app = resource.strip_prefix("api://").unwrap_or(resource);
+    if is_guid(app) {
+        Some(app.eq_ignore_ascii_case(aud))
+    } else if is_guid(aud) {
+        None
+    } else {
+        Some(false)
+    }
+}
+
+fn is_guid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A JWT payload, as far as we read it.
+#[derive(Deserialize)]
+struct Payload {
+    aud: Option<Audience>,
+    scp: Option<String>,
+    exp: Option<u64>,
+    upn: Option<String>,
+    preferred_username: Option<String>,
+    unique_name: Option<String>,
+}
+
+/// Decode the payload of a JWT.
+pub fn decode(token: &str) -> Result<Claims, String> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| String::from("token is not a JWT"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|err| format!("payload is not base64: {err}"))?;
+    let payload: Payload =
+        serde_json::from_slice(&payload).map_err(|err| format!("invalid payload: {err}"))?;
+
+    Ok(Claims {
+        aud: match payload.aud {
+            Some(Audience::One(aud)) => vec![aud],
+            Some(Audience::Many(auds)) => auds,
+            None => Vec::new(),
+        },
+        scp: payload
+            .scp
+            .map(|scp| scp.split_whitespace().map(String::from).collect())
+            .unwrap_or_default(),
+        exp: payload.exp,
+        user: payload
+            .upn
+            .or(payload.preferred_username)
+            .or(payload.unique_name),
+    })
+}
+
+/// A Graph token for tests, with the payload
+/// `{"aud":"https://graph.microsoft.com","scp":"User.Read Mail.Read","exp":1700000000}`.
+#[cfg(test)]
+pub const TOKEN: &str = "e30.eyJhdWQiOiJodHRwczovL2dyYXBoLm1pY3Jvc29mdC5jb20iLCJzY3AiOiJVc2VyLlJlYWQgTWFpbC5SZWFkIiwiZXhwIjoxNzAwMDAwMDAwfQ.sig";
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Claims, TOKEN};
+    use pretty_assertions::assert_eq;
+
+    fn claims(aud: &str, scp: &str) -> Claims {
+        Claims {
+            aud: vec![String::from(aud)],
+            scp: scp.split_whitespace().map(String::from).collect(),
+            exp: None,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn decode_audiences() {
+        // {"aud":["foo","bar"],"preferred_username":"foo@example.com"}
+        let token = "e30.eyJhdWQiOlsiZm9vIiwiYmFyIl0sInByZWZlcnJlZF91c2VybmFtZSI6ImZvb0BleGFtcGxlLmNvbSJ9.sig";
+        let subject = decode(token).unwrap();
+
+        assert_eq!(subject.aud, ["foo", "bar"]);
+        assert_eq!(subject.user.as_deref(), Some("foo@example.com"));
+    }
+
+    #[test]
+    fn decode_token() {
+        let subject = decode(TOKEN).unwrap();
+
+        assert_eq!(
+            subject,
+            Claims {
+                exp: Some(1700000000),
+                ..claims("https://graph.microsoft.com", "User.Read Mail.Read")
+            }
+        );
+    }
+
+    #[test]
+    fn decode_opaque_token() {
+        assert!(decode("opaque").is_err());
+    }
+
+    #[test]
+    fn covers_scopes() {
+        let subject = claims("https://graph.microsoft.com", "User.Read");
+
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/User.Read"),
+            Ok(true)
+        );
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/user.read"),
+            Ok(true)
+        );
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/.default"),
+            Ok(true)
+        );
+        assert_eq!(subject.covers("User.Read"), Ok(true));
+        assert_eq!(subject.covers("offline_access"), Ok(true));
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/Mail.Read"),
+            Ok(false)
+        );
+        assert_eq!(subject.covers("api://other/.default"), Ok(false));
+    }
+
+    #[test]
+    fn covers_app_id_audience() {
+        let subject = claims("6a4e5f2c-0c3d-4c1e-9a3b-1f2e3d4c5b6a", "access_as_user");
+
+        assert_eq!(
+            subject.covers("api://6a4e5f2c-0c3d-4c1e-9a3b-1f2e3d4c5b6a/access_as_user"),
+            Ok(true)
+        );
+        assert_eq!(
+            subject.covers("api://6a4e5f2c-0c3d-4c1e-9a3b-1f2e3d4c5b6a/.default"),
+            Ok(true)
+        );
+        assert_eq!(
+            subject.covers("api://0b8e3f1a-5c2d-4e6f-8a9b-7c6d5e4f3a2b/access_as_user"),
+            Ok(false)
+        );
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/access_as_user"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn covers_app_id_uri_audience() {
+        let subject = claims("6a4e5f2c-0c3d-4c1e-9a3b-1f2e3d4c5b6a", "access");
+
+        assert!(subject.covers("api://my-api/access").is_err());
+        assert!(subject
+            .covers("https://contoso.onmicrosoft.com/api/.default")
+            .is_err());
+    }
+
+    #[test]
+    fn covers_graph_app_id_audience() {
+        let subject = claims("00000003-0000-0000-c000-000000000000", "User.Read");
+
+        assert_eq!(
+            subject.covers("https://graph.microsoft.com/User.Read"),
+            Ok(true)
+        );
+    }
+}
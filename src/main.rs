@@ -1,18 +1,192 @@
-use std::process::Command;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
 
 use clap::Parser;
 
-#[derive(Parser)]
-struct Target {
+use config::Config;
+use token::{Format, TokenResult};
+
+mod account;
+mod cache;
+mod config;
+mod dirs;
+mod environment;
+mod hash;
+mod jwt;
+mod token;
+
+/// What to authenticate against, with anything left out taken from the
+/// nearest `.wrapperauth.toml`.
+#[derive(Default, Parser)]
+struct TargetArgs {
     /// Client ID.
     #[clap(long)]
-    client: String,
+    client: Option<String>,
     /// Tenant ID.
     #[clap(long)]
+    tenant: Option<String>,
+    /// Requested scopes, or @file to read them one per line from a file.
+    #[clap(long)]
+    scopes: Vec<String>,
+    /// Azure AD B2C user flow, like B2C_1_signupsignin.
+    #[clap(long)]
+    b2c_policy: Option<String>,
+    /// UPN of the account to use when several are cached by wrapperauth.
+    ///
+    /// AzureAuth has no option to pick an account, so this only chooses
+    /// between tokens in wrapperauth's own cache. On a cache miss AzureAuth
+    /// picks the account, and a token for any other account is rejected.
+    #[clap(long)]
+    account: Option<String>,
+    /// Pass AzureAuth the whole environment, not just an allow-list.
+    #[clap(long)]
+    inherit_env: bool,
+}
+
+impl TargetArgs {
+    fn resolve(&self, config: Config) -> Result<Target, String> {
+        let missing = |name| {
+            format!(
+                "No {name} given. Pass --{name} or set it in {}.",
+                config::FILE_NAME
+            )
+        };
+        let scopes = if self.scopes.is_empty() {
+            expand_scopes(&config.scopes, &config.dir)?
+        } else {
+            expand_scopes(&self.scopes, Path::new(""))?
+        };
+        if scopes.is_empty() {
+            return Err(missing("scopes"));
+        }
+        let client = self
+            .client
+            .clone()
+            .or(config.client)
+            .ok_or_else(|| missing("client"))?;
+        let tenant = self
+            .tenant
+            .clone()
+            .or(config.tenant)
+            .ok_or_else(|| missing("tenant"))?;
+        let authority = match self.b2c_policy.as_ref().or(config.b2c_policy.as_ref()) {
+            Some(policy) => Some(b2c_authority(&tenant, policy)?),
+            None => None,
+        };
+
+        Ok(Target {
+            client,
+            tenant,
+            scopes,
+            authority,
+            account: self.account.clone(),
+            env: config.env,
+            inherit_env: self.inherit_env,
+        })
+    }
+}
+
+/// Replace any `@file` scopes with the scopes listed in that file, with
+/// relative paths resolved against `dir`.
+fn expand_scopes(scopes: &[String], dir: &Path) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    for scope in scopes {
+        match scope.strip_prefix('@') {
+            Some(path) => {
+                let path = dir.join(path);
+                let contents = fs::read_to_string(&path).map_err(|err| {
+                    format!("Failed to read scopes from {}: {err}", path.display())
+                })?;
+                expanded.extend(parse_scopes(&contents));
+            }
+            None => expanded.push(scope.clone()),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Parse one scope per line, ignoring blank lines and `#` comments.
+fn parse_scopes(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+}
+
+/// Build the authority for an Azure AD B2C user flow.
+///
+/// B2C authorities are addressed by tenant name rather than ID, so `tenant`
+/// has to be `contoso` or `contoso.onmicrosoft.com`.
+fn b2c_authority(tenant: &str, policy: &str) -> Result<String, String> {
+    let name = tenant.strip_suffix(".onmicrosoft.com").unwrap_or(tenant);
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!(
+            "B2C needs a tenant name like contoso or contoso.onmicrosoft.com, not {tenant}."
+        ));
+    }
+    if policy.is_empty() || policy.contains('/') {
+        return Err(format!("Invalid B2C policy {policy:?}."));
+    }
+    let name = name.to_ascii_lowercase();
+    Ok(format!(
+        "https://{name}.b2clogin.com/tfp/{name}.onmicrosoft.com/{policy}"
+    ))
+}
+
+struct Target {
+    client: String,
     tenant: String,
-    /// Requested scopes.
-    #[clap(long, required = true)]
     scopes: Vec<String>,
+    /// An authority to use instead of the tenant's default one.
+    authority: Option<String>,
+    /// Which of several cached accounts to use.
+    account: Option<String>,
+    /// Variables to set for AzureAuth.
+    env: BTreeMap<String, String>,
+    inherit_env: bool,
+}
+
+impl Target {
+    /// Identifies this target's token in the cache, regardless of scope order.
+    ///
+    /// AzureAuth's environment overrides count too, since they can change
+    /// which token it hands out.
+    fn cache_key(&self) -> String {
+        let mut scopes: Vec<&str> = self.scopes.iter().map(String::as_str).collect();
+        scopes.sort_unstable();
+        let env: Vec<String> = self
+            .env
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect();
+        let id = [self.client.as_str(), self.tenant.as_str()]
+            .into_iter()
+            .chain(self.authority.as_deref())
+            .chain(scopes)
+            .chain(env.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("\0");
+        format!("{:016x}", hash::fnv1a(id.as_bytes()))
+    }
+}
+
+#[derive(Parser)]
+struct Auth {
+    #[clap(flatten)]
+    target: TargetArgs,
+    /// Fail if the token doesn't grant every requested scope.
+    #[clap(long)]
+    strict: bool,
+    /// Output format to request from AzureAuth.
+    #[clap(long, value_enum, default_value = "json")]
+    output: Format,
+    /// Output format to print the token in.
+    #[clap(long, value_enum, default_value = "token")]
+    format: Format,
 }
 
 /// Do the good auth.
@@ -20,45 +194,183 @@ struct Target {
 #[clap(version)]
 enum Args {
     /// Acquire a token.
-    Auth(Target),
+    Auth(Auth),
     /// Clear a token.
-    Clear(Target),
+    Clear(TargetArgs),
+}
+
+impl Args {
+    fn target(&self) -> &TargetArgs {
+        match self {
+            Args::Auth(auth) => &auth.target,
+            Args::Clear(target) => target,
+        }
+    }
 }
 
-impl From<Target> for Vec<String> {
-    fn from(target: Target) -> Self {
+impl From<&Target> for Vec<String> {
+    fn from(target: &Target) -> Self {
         let mut args = vec![
             String::from("--client"),
-            target.client,
+            target.client.clone(),
             String::from("--tenant"),
-            target.tenant,
+            target.tenant.clone(),
             String::from("--resource"),
             String::from(" "),
         ];
 
-        for scope in target.scopes {
+        if let Some(authority) = &target.authority {
+            args.push(String::from("--authority"));
+            args.push(authority.clone());
+        }
+
+        for scope in &target.scopes {
             args.push(String::from("--scope"));
-            args.push(scope);
+            args.push(scope.clone());
         }
 
         args
     }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
-    let args = translate(args);
-    let result = Command::new("azureauth").args(args).spawn();
+    let result = resolve(args.target()).and_then(|target| {
+        let command = translate(&args, &target);
+        match &args {
+            Args::Auth(auth) => authenticate(command, &target, auth),
+            Args::Clear(_) => clear(command, &target),
+        }
+    });
     match result {
-        Ok(_) => println!("Spawned AzureAuth process."),
-        Err(err) => eprintln!("Failed to spawn AzureAuth process: {err}"),
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn translate(args: Args) -> Vec<String> {
+/// Fill in the target from the nearest config file, if there is one.
+fn resolve(args: &TargetArgs) -> Result<Target, String> {
+    let cwd =
+        env::current_dir().map_err(|err| format!("Failed to get the current directory: {err}"))?;
+    let config = match config::discover(&cwd) {
+        Some(path) => config::load(&path)?,
+        None => Config::default(),
+    };
+    args.resolve(config)
+}
+
+/// An AzureAuth process, in the environment the target allows.
+fn azureauth(command: Vec<String>, target: &Target) -> Command {
+    let mut azureauth = Command::new("azureauth");
+    azureauth.args(command);
+    environment::apply(
+        &mut azureauth,
+        env::vars_os(),
+        &target.env,
+        target.inherit_env,
+    );
+    azureauth
+}
+
+fn authenticate(command: Vec<String>, target: &Target, auth: &Auth) -> Result<(), String> {
+    let key = target.cache_key();
+    if let Some(result) = account::select(cache::get(&key), target.account.as_deref())? {
+        if verify(&result.token, &target.scopes).is_ok() {
+            println!("{}", result.render(auth.format));
+            return Ok(());
+        }
+        // Never served again, so ask AzureAuth for a better one.
+        if let Err(err) = cache::evict(&key, &result) {
+            eprintln!("Warning: {err}");
+        }
+    }
+
+    let output = azureauth(command, target)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("Failed to spawn AzureAuth process: {err}"))?;
+    if !output.status.success() {
+        return Err(format!("AzureAuth process failed: {}", output.status));
+    }
+
+    let result = TokenResult::parse(auth.output, &String::from_utf8_lossy(&output.stdout))?;
+    if let Some(account) = &target.account {
+        match &result.user {
+            Some(user) if !account::matches(&result, account) => {
+                return Err(format!(
+                    "AzureAuth returned a token for {user} rather than {account}."
+                ));
+            }
+            Some(_) => {}
+            None => eprintln!("Warning: Could not confirm the token is for {account}."),
+        }
+    }
+    let verified = match verify(&result.token, &target.scopes) {
+        Ok(()) => true,
+        Err(problem) if auth.strict => return Err(problem),
+        Err(problem) => {
+            eprintln!("Warning: {problem}");
+            false
+        }
+    };
+    if verified && result.expiration_date.is_some() {
+        if let Err(err) = cache::put(&key, &result) {
+            eprintln!("Warning: {err}");
+        }
+    }
+    println!("{}", result.render(auth.format));
+    Ok(())
+}
+
+fn clear(command: Vec<String>, target: &Target) -> Result<(), String> {
+    cache::remove(&target.cache_key(), target.account.as_deref())?;
+    let status = azureauth(command, target)
+        .status()
+        .map_err(|err| format!("Failed to spawn AzureAuth process: {err}"))?;
+    if !status.success() {
+        return Err(format!("AzureAuth process failed: {status}"));
+    }
+    Ok(())
+}
+
+/// Check that a token actually grants the requested scopes.
+///
+/// Cached broker accounts can silently hand back a token for fewer scopes
+/// than were asked for. Callers warn about that, or fail if `--strict`.
+fn verify(token: &str, scopes: &[String]) -> Result<(), String> {
+    let claims =
+        jwt::decode(token).map_err(|err| format!("Could not verify token scopes: {err}"))?;
+    let mut missing = Vec::new();
+    for scope in scopes {
+        if !claims
+            .covers(scope)
+            .map_err(|err| format!("Could not verify token scopes: {err}"))?
+        {
+            missing.push(scope.as_str());
+        }
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Token does not grant requested scopes: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+fn translate(args: &Args, target: &Target) -> Vec<String> {
     match args {
-        Args::Auth(target) => Vec::from(target),
-        Args::Clear(target) => {
+        Args::Auth(auth) => {
+            let mut args = Vec::from(target);
+            args.extend([String::from("--output"), String::from(auth.output.name())]);
+            args
+        }
+        Args::Clear(_) => {
             let mut args = Vec::from(target);
             args.push(String::from("--clear"));
             args
@@ -68,8 +380,15 @@ fn translate(args: Args) -> Vec<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::{translate, Args, Target};
+    use super::{
+        b2c_authority, expand_scopes, parse_scopes, translate, verify, Args, Auth, Config, Format,
+        Target, TargetArgs,
+    };
+    use crate::jwt::TOKEN;
     use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::{env, fs};
 
     const EXPECTED: [&str; 8] = [
         "--client",
@@ -82,54 +401,263 @@ mod tests {
         "baz",
     ];
 
-    #[test]
-    fn auth_command() {
-        let args = Args::Auth(Target {
+    fn target(scopes: &[&str]) -> Target {
+        Target {
             client: String::from("foo"),
             tenant: String::from("bar"),
-            scopes: vec![String::from("baz")],
-        });
-        let subject = translate(args);
+            scopes: scopes.iter().map(|scope| String::from(*scope)).collect(),
+            authority: None,
+            account: None,
+            env: BTreeMap::new(),
+            inherit_env: false,
+        }
+    }
+
+    fn auth() -> Args {
+        Args::Auth(Auth {
+            target: TargetArgs::default(),
+            strict: false,
+            output: Format::Token,
+            format: Format::Token,
+        })
+    }
+
+    fn clear() -> Args {
+        Args::Clear(TargetArgs::default())
+    }
+
+    #[test]
+    fn auth_command() {
+        let expected = [&EXPECTED[..], &["--output", "token"]].concat();
+        let subject = translate(&auth(), &target(&["baz"]));
 
-        assert_eq!(subject, EXPECTED);
+        assert_eq!(subject, expected);
     }
 
     #[test]
     fn auth_command_multiple_scopes() {
-        let args = Args::Auth(Target {
-            client: String::from("foo"),
-            tenant: String::from("bar"),
-            scopes: vec![String::from("baz"), String::from("quux")],
-        });
-        let expected = [&EXPECTED[..], &["--scope", "quux"]].concat();
-        let subject = translate(args);
+        let expected = [&EXPECTED[..], &["--scope", "quux", "--output", "token"]].concat();
+        let subject = translate(&auth(), &target(&["baz", "quux"]));
 
         assert_eq!(subject, expected);
     }
 
     #[test]
     fn clear_command() {
-        let args = Args::Clear(Target {
-            client: String::from("foo"),
-            tenant: String::from("bar"),
-            scopes: vec![String::from("baz")],
-        });
         let expected = [&EXPECTED[..], &["--clear"]].concat();
-        let subject = translate(args);
+        let subject = translate(&clear(), &target(&["baz"]));
 
         assert_eq!(subject, expected);
     }
 
     #[test]
     fn clear_command_multiple_scopes() {
-        let args = Args::Clear(Target {
-            client: String::from("foo"),
-            tenant: String::from("bar"),
-            scopes: vec![String::from("baz"), String::from("quux")],
-        });
         let expected = [&EXPECTED[..], &["--scope", "quux", "--clear"]].concat();
-        let subject = translate(args);
+        let subject = translate(&clear(), &target(&["baz", "quux"]));
+
+        assert_eq!(subject, expected);
+    }
+
+    #[test]
+    fn auth_command_b2c() {
+        let target = Target {
+            authority: Some(String::from(
+                "https://contoso.b2clogin.com/tfp/contoso.onmicrosoft.com/B2C_1_signin",
+            )),
+            ..target(&["baz"])
+        };
+        let expected = [
+            &EXPECTED[..6],
+            &[
+                "--authority",
+                "https://contoso.b2clogin.com/tfp/contoso.onmicrosoft.com/B2C_1_signin",
+            ],
+            &EXPECTED[6..],
+            &["--output", "token"],
+        ]
+        .concat();
+        let subject = translate(&auth(), &target);
 
         assert_eq!(subject, expected);
     }
+
+    #[test]
+    fn parse_scope_file() {
+        let contents =
+            "# Graph.\nhttps://graph.microsoft.com/User.Read\n\n  offline_access  # Refresh.\n";
+        let subject: Vec<String> = parse_scopes(contents).collect();
+
+        assert_eq!(
+            subject,
+            ["https://graph.microsoft.com/User.Read", "offline_access"]
+        );
+    }
+
+    #[test]
+    fn expand_scope_file() {
+        let path = env::temp_dir().join(format!("wrapperauth-scopes-{}", std::process::id()));
+        fs::write(&path, "quux\n").unwrap();
+        let scopes = [String::from("baz"), format!("@{}", path.display())];
+
+        let subject = expand_scopes(&scopes, Path::new(""));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(subject, Ok(vec![String::from("baz"), String::from("quux")]));
+    }
+
+    #[test]
+    fn resolve_config_scope_file() {
+        let dir = env::temp_dir().join(format!("wrapperauth-config-scopes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("scopes.txt"), "baz\n").unwrap();
+        let args = TargetArgs {
+            client: Some(String::from("foo")),
+            tenant: Some(String::from("bar")),
+            ..TargetArgs::default()
+        };
+        let config = Config {
+            scopes: vec![String::from("@scopes.txt")],
+            dir: dir.clone(),
+            ..Config::default()
+        };
+
+        let subject = args.resolve(config);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            translate(&auth(), &subject.unwrap()),
+            translate(&auth(), &target(&["baz"]))
+        );
+    }
+
+    #[test]
+    fn expand_missing_scope_file() {
+        assert!(expand_scopes(&[String::from("@/nonexistent/scopes.txt")], Path::new("")).is_err());
+    }
+
+    #[test]
+    fn b2c_authority_from_tenant_name() {
+        let expected = "https://contoso.b2clogin.com/tfp/contoso.onmicrosoft.com/B2C_1_signin";
+
+        assert_eq!(
+            b2c_authority("contoso", "B2C_1_signin").as_deref(),
+            Ok(expected)
+        );
+        assert_eq!(
+            b2c_authority("Contoso.onmicrosoft.com", "B2C_1_signin").as_deref(),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn b2c_authority_rejects_tenant_id() {
+        assert!(b2c_authority("6a4e5f2c-0c3d-4c1e-9a3b-1f2e3d4c5b6a", "B2C_1_signin").is_err());
+        assert!(b2c_authority("contoso", "").is_err());
+    }
+
+    #[test]
+    fn cache_key_ignores_scope_order() {
+        let subject = target(&["baz", "quux"]).cache_key();
+
+        assert_eq!(subject, target(&["quux", "baz"]).cache_key());
+        assert_ne!(subject, target(&["baz"]).cache_key());
+    }
+
+    #[test]
+    fn cache_key_includes_env() {
+        let subject = Target {
+            env: BTreeMap::from([(
+                String::from("AZUREAUTH_CONFIG"),
+                String::from("/etc/azureauth.toml"),
+            )]),
+            ..target(&["baz"])
+        };
+
+        assert_ne!(subject.cache_key(), target(&["baz"]).cache_key());
+    }
+
+    #[test]
+    fn resolve_from_args() {
+        let args = TargetArgs {
+            client: Some(String::from("foo")),
+            tenant: Some(String::from("bar")),
+            scopes: vec![String::from("baz")],
+            b2c_policy: None,
+            account: None,
+            inherit_env: false,
+        };
+        let config = Config {
+            client: Some(String::from("other")),
+            tenant: Some(String::from("other")),
+            scopes: vec![String::from("other")],
+            b2c_policy: None,
+            env: BTreeMap::new(),
+            dir: PathBuf::new(),
+        };
+        let subject = args.resolve(config).unwrap();
+
+        assert_eq!(
+            translate(&auth(), &subject),
+            translate(&auth(), &target(&["baz"]))
+        );
+    }
+
+    #[test]
+    fn resolve_from_config() {
+        let args = TargetArgs {
+            client: Some(String::from("foo")),
+            ..TargetArgs::default()
+        };
+        let config = Config {
+            tenant: Some(String::from("bar")),
+            scopes: vec![String::from("baz")],
+            ..Config::default()
+        };
+        let subject = args.resolve(config).unwrap();
+
+        assert_eq!(
+            translate(&auth(), &subject),
+            translate(&auth(), &target(&["baz"]))
+        );
+    }
+
+    #[test]
+    fn resolve_missing_values() {
+        let args = TargetArgs {
+            scopes: vec![String::from("baz")],
+            ..TargetArgs::default()
+        };
+        let subject = args.resolve(Config::default()).err();
+
+        assert_eq!(
+            subject.as_deref(),
+            Some("No client given. Pass --client or set it in .wrapperauth.toml.")
+        );
+    }
+
+    #[test]
+    fn verify_granted_scopes() {
+        let scopes = [String::from("https://graph.microsoft.com/User.Read")];
+
+        assert_eq!(verify(TOKEN, &scopes), Ok(()));
+    }
+
+    #[test]
+    fn verify_missing_scopes() {
+        let scopes = [String::from("https://graph.microsoft.com/Files.Read")];
+
+        assert_eq!(
+            verify(TOKEN, &scopes),
+            Err(String::from(
+                "Token does not grant requested scopes: https://graph.microsoft.com/Files.Read"
+            ))
+        );
+    }
+
+    #[test]
+    fn verify_opaque_token() {
+        let scopes = [String::from("baz")];
+
+        assert!(verify("opaque", &scopes).is_err());
+    }
 }
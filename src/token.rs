@@ -0,0 +1,175 @@
+//! Tokens as AzureAuth reports them, in any of its output formats.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::jwt;
+
+/// AzureAuth's output formats, which we also speak.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// A JSON object with the token and account details.
+    Json,
+    /// Only the token.
+    Token,
+    /// Human readable `Key: value` lines.
+    Status,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Token => "token",
+            Format::Status => "status",
+        }
+    }
+}
+
+/// An acquired token, however it was reported.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TokenResult {
+    pub user: Option<String>,
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub token: String,
+    /// Seconds since the Unix epoch.
+    pub expiration_date: Option<u64>,
+}
+
+impl TokenResult {
+    /// Parse AzureAuth's output in the given format.
+    ///
+    /// A missing user or expiration date is filled in from the token itself.
+    pub fn parse(format: Format, output: &str) -> Result<Self, String> {
+        let mut result = match format {
+            Format::Json => Self::parse_json(output)?,
+            Format::Token => TokenResult {
+                user: None,
+                display_name: None,
+                token: String::from(output.trim()),
+                expiration_date: None,
+            },
+            Format::Status => Self::parse_status(output),
+        };
+        if result.token.is_empty() {
+            return Err(format!("No token in AzureAuth {} output", format.name()));
+        }
+        if let Ok(claims) = jwt::decode(&result.token) {
+            result.user = result.user.or(claims.user);
+            result.expiration_date = result.expiration_date.or(claims.exp);
+        }
+        Ok(result)
+    }
+
+    fn parse_json(output: &str) -> Result<Self, String> {
+        serde_json::from_str(output).map_err(|err| format!("Invalid AzureAuth json output: {err}"))
+    }
+
+    fn parse_status(output: &str) -> Self {
+        let mut result = TokenResult {
+            user: None,
+            display_name: None,
+            token: String::new(),
+            expiration_date: None,
+        };
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "User" => result.user = Some(String::from(value)),
+                "Display Name" => result.display_name = Some(String::from(value)),
+                "Token" => result.token = String::from(value),
+                "Expiration Date" => result.expiration_date = value.parse().ok(),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Render in the given format.
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Json => serde_json::to_string(self).expect("token results serialize"),
+            Format::Token => self.token.clone(),
+            Format::Status => {
+                let mut lines = Vec::new();
+                if let Some(user) = &self.user {
+                    lines.push(format!("User: {user}"));
+                }
+                if let Some(display_name) = &self.display_name {
+                    lines.push(format!("Display Name: {display_name}"));
+                }
+                lines.push(format!("Token: {}", self.token));
+                if let Some(expiration_date) = self.expiration_date {
+                    lines.push(format!("Expiration Date: {expiration_date}"));
+                }
+                lines.join("\n")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, TokenResult};
+    use crate::jwt::TOKEN;
+    use pretty_assertions::assert_eq;
+
+    fn result() -> TokenResult {
+        TokenResult {
+            user: Some(String::from("foo@example.com")),
+            display_name: Some(String::from("Foo")),
+            token: String::from("opaque"),
+            expiration_date: Some(1800000000),
+        }
+    }
+
+    #[test]
+    fn parse_json() {
+        let output = r#"{"user": "foo@example.com", "display_name": "Foo", "token": "opaque", "expiration_date": 1800000000}"#;
+
+        assert_eq!(TokenResult::parse(Format::Json, output), Ok(result()));
+    }
+
+    #[test]
+    fn parse_token() {
+        let subject = TokenResult::parse(Format::Token, &format!("{TOKEN}\n")).unwrap();
+
+        assert_eq!(subject.token, TOKEN);
+        assert_eq!(subject.expiration_date, Some(1700000000));
+    }
+
+    #[test]
+    fn parse_token_user() {
+        // {"upn":"foo@example.com"}
+        let token = "e30.eyJ1cG4iOiJmb29AZXhhbXBsZS5jb20ifQ.sig";
+        let subject = TokenResult::parse(Format::Token, token).unwrap();
+
+        assert_eq!(subject.user.as_deref(), Some("foo@example.com"));
+    }
+
+    #[test]
+    fn parse_status() {
+        let output = "Acquired a token.\nUser: foo@example.com\nDisplay Name: Foo\nToken: opaque\nExpiration Date: 1800000000\n";
+
+        assert_eq!(TokenResult::parse(Format::Status, output), Ok(result()));
+    }
+
+    #[test]
+    fn parse_missing_token() {
+        assert!(TokenResult::parse(Format::Token, "\n").is_err());
+        assert!(TokenResult::parse(Format::Json, "{}").is_err());
+    }
+
+    #[test]
+    fn render_round_trip() {
+        for format in [Format::Json, Format::Status] {
+            let subject = TokenResult::parse(format, &result().render(format));
+
+            assert_eq!(subject, Ok(result()));
+        }
+    }
+}